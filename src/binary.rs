@@ -0,0 +1,159 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{Read, Write, BufReader, BufWriter};
+
+use ndarray::Array3;
+
+use crate::VoxelModel;
+
+/// Voxel payload is stored as-is, one byte per voxel
+const ENCODING_RAW: u8 = 0;
+/// Voxel payload is stored as `(value: u8, run_length: u32)` pairs
+const ENCODING_RLE: u8 = 1;
+
+/// Write a voxel model to a compact binary .vf file, mirroring the grid
+/// the text writer produces. Picks whichever of raw or run-length encoding
+/// is smaller for this particular grid, since a near-random voxel grid can
+/// make RLE larger than just storing the bytes.
+pub fn write_to_vf_binary(model: &VoxelModel, file_name: &str) -> std::io::Result<()> {
+    let mut file = BufWriter::new(File::create(file_name)?);
+
+    write_header(&mut file, model)?;
+
+    // Walk the voxels in the same order as the text writer and RLE-encode them
+    let mut raw = Vec::with_capacity(model.x_len * model.y_len * model.z_len);
+    let mut runs: Vec<(u8, u32)> = Vec::new();
+    for x in 0..model.x_len {
+        for z in 0..model.z_len {
+            for y in 0..model.y_len {
+                let value = model.voxels[[x, y, z]];
+                raw.push(value);
+
+                match runs.last_mut() {
+                    Some((v, run_length)) if *v == value => *run_length += 1,
+                    _ => runs.push((value, 1)),
+                }
+            }
+        }
+    }
+
+    let raw_size = raw.len();
+    let rle_size = runs.len() * 5;
+
+    if rle_size < raw_size {
+        file.write_all(&[ENCODING_RLE])?;
+        for (value, run_length) in runs {
+            file.write_all(&[value])?;
+            file.write_all(&run_length.to_le_bytes())?;
+        }
+    } else {
+        file.write_all(&[ENCODING_RAW])?;
+        file.write_all(&raw)?;
+    }
+
+    return Ok(());
+}
+
+/// Write the `x_len,y_len,z_len` header, resolution, origin, and materials table
+fn write_header(file: &mut BufWriter<File>, model: &VoxelModel) -> std::io::Result<()> {
+    file.write_all(&(model.x_len as u32).to_le_bytes())?;
+    file.write_all(&(model.y_len as u32).to_le_bytes())?;
+    file.write_all(&(model.z_len as u32).to_le_bytes())?;
+
+    file.write_all(&model.resolution.to_le_bytes())?;
+    for coord in &model.origin {
+        file.write_all(&coord.to_le_bytes())?;
+    }
+
+    file.write_all(&(model.materials.len() as u32).to_le_bytes())?;
+    for row in &model.materials {
+        for value in row {
+            file.write_all(&value.to_le_bytes())?;
+        }
+    }
+
+    return Ok(());
+}
+
+/// Read a .vf file back into a `VoxelModel`, whether it was written raw or run-length encoded
+pub fn read_vf(file_name: &str) -> Result<VoxelModel, Box<dyn Error>> {
+    let mut file = BufReader::new(File::open(file_name)?);
+
+    let x_len = read_u32(&mut file)? as usize;
+    let y_len = read_u32(&mut file)? as usize;
+    let z_len = read_u32(&mut file)? as usize;
+
+    let resolution = read_f32(&mut file)?;
+    let origin = [read_f32(&mut file)?, read_f32(&mut file)?, read_f32(&mut file)?];
+
+    let n_materials = read_u32(&mut file)? as usize;
+    let mut materials = Vec::with_capacity(n_materials);
+    for _ in 0..n_materials {
+        let mut row = [0.0; 10];
+        for value in &mut row {
+            *value = read_f32(&mut file)?;
+        }
+        materials.push(row);
+    }
+
+    let mut encoding = [0u8; 1];
+    file.read_exact(&mut encoding)?;
+
+    let total = x_len * y_len * z_len;
+    let mut voxels: Array3<u8> = Array3::zeros((x_len, y_len, z_len));
+    let mut flat_index = 0;
+
+    match encoding[0] {
+        ENCODING_RAW => {
+            let mut buf = vec![0u8; total];
+            file.read_exact(&mut buf)?;
+
+            for value in buf {
+                let (x, y, z) = unflatten_index(flat_index, y_len, z_len);
+                voxels[[x, y, z]] = value;
+                flat_index += 1;
+            }
+        },
+        ENCODING_RLE => {
+            while flat_index < total {
+                let mut value = [0u8; 1];
+                file.read_exact(&mut value)?;
+                let run_length = read_u32(&mut file)? as usize;
+
+                for _ in 0..run_length {
+                    let (x, y, z) = unflatten_index(flat_index, y_len, z_len);
+                    voxels[[x, y, z]] = value[0];
+                    flat_index += 1;
+                }
+            }
+        },
+        other => return Err(format!("unknown voxel encoding byte {}", other).into())
+    }
+
+    return Ok(VoxelModel { voxels, x_len, y_len, z_len, materials, origin, resolution });
+}
+
+/// Turn a flat index from the `x` outer, `z` middle, `y` inner iteration order
+/// the writers use back into `(x, y, z)` grid coordinates
+fn unflatten_index(flat: usize, y_len: usize, z_len: usize) -> (usize, usize, usize) {
+    let x = flat / (z_len * y_len);
+    let remainder = flat % (z_len * y_len);
+    let z = remainder / y_len;
+    let y = remainder % y_len;
+
+    return (x, y, z);
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> std::io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+
+    return Ok(u32::from_le_bytes(buf));
+}
+
+fn read_f32<R: Read>(reader: &mut R) -> std::io::Result<f32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+
+    return Ok(f32::from_le_bytes(buf));
+}
@@ -0,0 +1,145 @@
+/// Axis-aligned bounding box, used to cheaply reject tets that can't contain a point
+#[derive(Clone, Copy)]
+pub struct AABB {
+    pub min: [f32; 3],
+    pub max: [f32; 3]
+}
+
+impl AABB {
+    pub fn new(min: [f32; 3], max: [f32; 3]) -> AABB {
+        return AABB { min, max };
+    }
+
+    /// Build the smallest box containing every point in `points`
+    pub fn from_points(points: &[[f32; 3]]) -> AABB {
+        let mut min = points[0];
+        let mut max = points[0];
+
+        for point in &points[1..] {
+            for axis in 0..3 {
+                if point[axis] < min[axis] { min[axis] = point[axis]; }
+                if point[axis] > max[axis] { max[axis] = point[axis]; }
+            }
+        }
+
+        return AABB { min, max };
+    }
+
+    /// Check if a point falls within this box
+    pub fn contains(&self, point: [f32; 3]) -> bool {
+        for axis in 0..3 {
+            if point[axis] < self.min[axis] || point[axis] > self.max[axis] {
+                return false;
+            }
+        }
+
+        return true;
+    }
+
+    /// Smallest box that contains both `self` and `other`
+    pub fn union(&self, other: &AABB) -> AABB {
+        let mut min = self.min;
+        let mut max = self.max;
+
+        for axis in 0..3 {
+            if other.min[axis] < min[axis] { min[axis] = other.min[axis]; }
+            if other.max[axis] > max[axis] { max[axis] = other.max[axis]; }
+        }
+
+        return AABB { min, max };
+    }
+
+    /// Index (0 = x, 1 = y, 2 = z) of the axis this box is longest along
+    pub fn longest_axis(&self) -> usize {
+        let extents = [
+            self.max[0] - self.min[0],
+            self.max[1] - self.min[1],
+            self.max[2] - self.min[2]
+        ];
+
+        let mut axis = 0;
+        for i in 1..3 {
+            if extents[i] > extents[axis] { axis = i; }
+        }
+
+        return axis;
+    }
+
+    pub fn centroid(&self) -> [f32; 3] {
+        return [
+            (self.min[0] + self.max[0]) / 2.0,
+            (self.min[1] + self.max[1]) / 2.0,
+            (self.min[2] + self.max[2]) / 2.0
+        ];
+    }
+}
+
+/// Number of boxes at or below which a BVH node stops splitting and becomes a leaf
+const LEAF_SIZE: usize = 4;
+
+/// Bounding-volume hierarchy over a set of tetrahedron indices, letting a point
+/// query find the handful of tets whose box could contain it without testing
+/// every tet in the mesh
+pub enum BVH {
+    Node(Box<BVH>, Box<BVH>, AABB),
+    Leaf(AABB, Vec<(AABB, usize)>)
+}
+
+impl BVH {
+    /// Build a BVH from a list of (tet box, tet index) pairs, recursively
+    /// splitting along the longest axis of the current bounding box at the
+    /// median centroid until a leaf holds `LEAF_SIZE` tets or fewer
+    pub fn build(mut boxes: Vec<(AABB, usize)>) -> BVH {
+        if boxes.is_empty() {
+            return BVH::Leaf(AABB::new([0.0; 3], [0.0; 3]), Vec::new());
+        }
+
+        let mut bounds = boxes[0].0;
+        for (b, _) in &boxes[1..] {
+            bounds = bounds.union(b);
+        }
+
+        if boxes.len() <= LEAF_SIZE {
+            return BVH::Leaf(bounds, boxes);
+        }
+
+        let axis = bounds.longest_axis();
+        boxes.sort_by(|(a, _), (b, _)| a.centroid()[axis].partial_cmp(&b.centroid()[axis]).unwrap());
+
+        let right = boxes.split_off(boxes.len() / 2);
+        let left = boxes;
+
+        return BVH::Node(
+            Box::new(BVH::build(left)),
+            Box::new(BVH::build(right)),
+            bounds
+        );
+    }
+
+    /// Collect the indices of every leaf tet whose own box contains `point`,
+    /// not just the leaf's loose union box (a leaf dominated by an outlier tet
+    /// would otherwise hand back every tet it holds)
+    pub fn query(&self, point: [f32; 3], candidates: &mut Vec<usize>) {
+        match self {
+            BVH::Node(left, right, bounds) => {
+                if !bounds.contains(point) {
+                    return;
+                }
+
+                left.query(point, candidates);
+                right.query(point, candidates);
+            },
+            BVH::Leaf(bounds, boxes) => {
+                if !bounds.contains(point) {
+                    return;
+                }
+
+                for (tet_box, index) in boxes {
+                    if tet_box.contains(point) {
+                        candidates.push(*index);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,20 +1,34 @@
 use std::error::Error;
 use std::fs::File;
 use std::io::{BufRead, BufReader, Write, BufWriter};
-use std::path::Path;
 use std::process::Command;
 use std::thread;
 
-use ndarray::{Array1, Array2, Array3};
+use ndarray::{Array1, Array2, Array3, Zip};
 use ndarray_linalg::*;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+
+mod bvh;
+use bvh::{AABB, BVH};
+
+mod binary;
+pub use binary::{write_to_vf_binary, read_vf};
+
+/// Index into a `VoxelModel`'s materials table; voxel value `0` is always background,
+/// so `0` is reserved and `voxelize_multi` rejects it as a mesh's material id
+pub type MaterialId = u8;
 
 /// Data structure representing a voxel model
 pub struct VoxelModel {
-    voxels: Array3<u8>,
-    x_len: usize,
-    y_len: usize,
-    z_len: usize
+    pub(crate) voxels: Array3<u8>,
+    pub(crate) x_len: usize,
+    pub(crate) y_len: usize,
+    pub(crate) z_len: usize,
+    pub(crate) materials: Vec<[f32; 10]>,
+    /// Model-space coordinates of the grid's minimum corner (the corner of voxel `[0, 0, 0]`)
+    pub(crate) origin: [f32; 3],
+    /// Edge length of a voxel, in model units
+    pub(crate) resolution: f32
 }
 
 /// Mesh data structure
@@ -24,18 +38,29 @@ pub struct Mesh {
     tets: Array2<i32>
 }
 
+/// Placeholder material properties for a given material id, in the absence
+/// of a real materials database; background (id 0) is all zero
+fn default_material_row(material: MaterialId) -> [f32; 10] {
+    let m = material as f32;
+    return [m, 0.0, m, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+}
+
 /// Write a voxel model to a .vf file
 pub fn write_to_vf(model: &VoxelModel, file_name: &str) -> std::io::Result<()> {
     // Open a file
     let mut file = BufWriter::new(File::create(file_name)?);
 
     // Write coordinates
-    writeln!(file, "<coords>\n0,0,0,\n</coords>")?;
+    writeln!(file, "<coords>\n{},{},{},\n</coords>", model.origin[0], model.origin[1], model.origin[2])?;
 
     // Write materials
     writeln!(file, "<materials>")?;
-    writeln!(file, "0.0,0.0,0.0,0.0,0.0,0.0,0.0,0.0,0.0,0.0,")?;
-    writeln!(file, "1.0,0.0,1.0,0.0,0.0,0.0,0.0,0.0,0.0,0.0,")?;
+    for row in &model.materials {
+        for value in row {
+            write!(file, "{},", value)?;
+        }
+        writeln!(file)?;
+    }
     writeln!(file, "</materials>")?;
 
     // Write size
@@ -62,8 +87,15 @@ pub fn write_to_vf(model: &VoxelModel, file_name: &str) -> std::io::Result<()> {
 
 /// Create a mesh from an STL file
 pub fn from_stl(filename: &str) -> Result<Mesh, Box<dyn Error>> {
+    // Scratch files live in their own temp directory, so two concurrent
+    // invocations never clobber a shared output.geo/output.msh, and the
+    // directory is removed automatically once it goes out of scope
+    let scratch_dir = tempfile::tempdir()?;
+    let geo_path = scratch_dir.path().join("output.geo");
+    let msh_path = scratch_dir.path().join("output.msh");
+
     // Create a geo file for gmsh to use
-    let mut gmsh_script_file = File::create("output.geo")?;
+    let mut gmsh_script_file = File::create(&geo_path)?;
 
     // Write
     writeln!(gmsh_script_file, "Merge \"{}\";", filename)?;
@@ -72,57 +104,124 @@ pub fn from_stl(filename: &str) -> Result<Mesh, Box<dyn Error>> {
     writeln!(gmsh_script_file, "Volume(1) = {{1}};")?;
 
     // Use gmsh to convert STL file to mesh file
-    Command::new("gmsh")
-        .arg("output.geo")
+    let gmsh_output = Command::new("gmsh")
+        .arg(&geo_path)
         .arg("-3")
         .arg("-format")
         .arg("msh")
+        .arg("-o")
+        .arg(&msh_path)
         .output()?;
 
-    // List of points
+    // Surface a nonzero gmsh exit as a real error instead of silently continuing
+    if !gmsh_output.status.success() {
+        return Err(format!(
+            "gmsh exited with {}: {}",
+            gmsh_output.status,
+            String::from_utf8_lossy(&gmsh_output.stderr)
+        ).into());
+    }
+
+    // Open msh file created by gmsh
+    let file = File::open(&msh_path)?;
+
+    // Create a vector holding all the lines in the mesh file
+    let mut lines: Vec<String> = Vec::new();
+    for line in BufReader::new(file).lines() {
+        lines.push(line?.trim().to_owned());
+    }
+
+    return parse_msh(&lines);
+}
+
+/// Parse the lines of a .msh file produced by gmsh, auto-detecting whether
+/// it's the legacy MSH 2.2 format or the newer 4.1 block format
+fn parse_msh(lines: &[String]) -> Result<Mesh, Box<dyn Error>> {
+    let format_start = lines.iter().position(|line| line == "$MeshFormat")
+        .ok_or("msh file is missing a $MeshFormat section")?;
+    let version_line = split_string(&lines[format_start + 1]);
+    let version: f32 = version_line[0].parse()?;
+
+    let nodes_start = lines.iter().position(|line| line == "$Nodes")
+        .ok_or("msh file is missing a $Nodes section")?;
+    let elements_start = lines.iter().position(|line| line == "$Elements")
+        .ok_or("msh file is missing an $Elements section")?;
+
+    // MSH 2.2 and 4.1 lay out the Nodes/Elements blocks completely differently
+    if version < 3.0 {
+        return parse_msh_v2(lines, nodes_start, elements_start);
+    }
+
+    return parse_msh_v4(lines, nodes_start, elements_start);
+}
+
+/// Parse the Nodes/Elements blocks of the legacy MSH 2.2 format, where
+/// `$Nodes` is a flat `count` followed by `id x y z` lines and `$Elements`
+/// is a flat `count` followed by `id type ntags tags... nodes...` lines
+fn parse_msh_v2(lines: &[String], nodes_start: usize, elements_start: usize) -> Result<Mesh, Box<dyn Error>> {
     let mut points: Vec<f32> = Vec::new();
-    // List of tetrahedrons
     let mut tets: Vec<i32> = Vec::new();
 
-    let mut n_points = 0;
+    let n_points: usize = split_string(&lines[nodes_start + 1])[0].parse()?;
+    for i in 0..n_points {
+        let point_line = split_string(&lines[nodes_start + 2 + i]);
+        let x: f32 = point_line[1].parse()?;
+        let y: f32 = point_line[2].parse()?;
+        let z: f32 = point_line[3].parse()?;
 
-    // Open msh file created by gmsh
-    let file_path = Path::new("output.msh");
-    let file = File::open(&file_path)?;
+        points.extend_from_slice(&[x, y, z]);
+    }
 
-    // Current line number being processed
-    let mut line_number;
+    let n_elements: usize = split_string(&lines[elements_start + 1])[0].parse()?;
+    let mut n_tets = 0;
+    for i in 0..n_elements {
+        let element_line = split_string(&lines[elements_start + 2 + i]);
+        let element_type: i32 = element_line[1].parse()?;
 
-    // Line number of the start of the nodes block
-    let mut nodes_start = 0;
-    // Line number of the start of the elements block
-    let mut elements_start = 0;
+        // Element type 4 is a 4-node tetrahedron; every other type is skipped
+        if element_type != 4 {
+            continue;
+        }
 
-    // Create a vector holding all the line in the mesh file
-    let mut lines: Vec<String> = Vec::new();
-    for line in BufReader::new(file).lines() {
-        let line = line?.trim().to_owned();
+        let n_tags: usize = element_line[2].parse()?;
+        let node_start = 3 + n_tags;
 
-        // Save the positions of the starts of the Nodes and Elements section in the file
-        if &line == "$Nodes" {
-            nodes_start = lines.len();
-        } else if &line == "$Elements" {
-            elements_start = lines.len();
-        }
+        let a: i32 = element_line[node_start].parse()?;
+        let b: i32 = element_line[node_start + 1].parse()?;
+        let c: i32 = element_line[node_start + 2].parse()?;
+        let d: i32 = element_line[node_start + 3].parse()?;
 
-        lines.push(line);
+        tets.extend_from_slice(&[a - 1, b - 1, c - 1, d - 1]);
+        n_tets += 1;
     }
 
+    let points = Array2::from_shape_vec((n_points, 3), points)?;
+    let tets = Array2::from_shape_vec((n_tets, 4), tets)?;
+
+    return Ok(Mesh { points, tets });
+}
+
+/// Parse the Nodes/Elements blocks of the MSH 4.1 format, where both
+/// sections are split into blocks prefixed with a header line
+fn parse_msh_v4(lines: &[String], nodes_start: usize, elements_start: usize) -> Result<Mesh, Box<dyn Error>> {
+    // List of points
+    let mut points: Vec<f32> = Vec::new();
+    // List of tetrahedrons
+    let mut tets: Vec<i32> = Vec::new();
+
+    let mut n_points = 0;
+    let mut n_tets = 0;
+
     // Read the header line of the Nodes section and get the number of point blocks
-    let nodes_info_line = split_string(&lines[nodes_start+1]);
-    let point_blocks = nodes_info_line[0].parse()?;
+    let nodes_info_line = split_string(&lines[nodes_start + 1]);
+    let point_blocks: usize = nodes_info_line[0].parse()?;
 
-    line_number = nodes_start + 2;
+    let mut line_number = nodes_start + 2;
     // Process every point block
     for _ in 0..point_blocks {
         // Read the block header line and get the number of points in the block
         let block_info_line = split_string(&lines[line_number]);
-        let n = block_info_line[3].parse()?;
+        let n: usize = block_info_line[3].parse()?;
         line_number += n + 1 as usize;
         // Read all the points in the block and save them
         for _ in 0..n {
@@ -138,53 +237,54 @@ pub fn from_stl(filename: &str) -> Result<Mesh, Box<dyn Error>> {
         }
     }
 
-    // Get the number of tris
-    let tris_info_line = split_string(&lines[elements_start+2]);
-    let n_tris: usize = tris_info_line[3].parse()?;
+    // Read the header line of the Elements section and get the number of entity blocks
+    let elements_info_line = split_string(&lines[elements_start + 1]);
+    let entity_blocks: usize = elements_info_line[0].parse()?;
 
-    // Get the number of tets
-    line_number = elements_start + n_tris + 3;
-    let tets_info_line = split_string(&lines[line_number]);
-    let n_tets = tets_info_line[3].parse()?;
-
-    line_number += 1;
-    // Read all the tets and save them
-    for _ in 0..n_tets {
-        let point_line = split_string(&lines[line_number]);
-        let a: i32 = point_line[1].parse()?;
-        let b: i32 = point_line[2].parse()?;
-        let c: i32 = point_line[3].parse()?;
-        let d: i32 = point_line[4].parse()?;
+    line_number = elements_start + 2;
+    // Process every entity block, selecting tets by their element type code
+    // rather than assuming a fixed tris-then-tets block ordering
+    for _ in 0..entity_blocks {
+        let block_info_line = split_string(&lines[line_number]);
+        let element_type: i32 = block_info_line[2].parse()?;
+        let n: usize = block_info_line[3].parse()?;
+        line_number += 1;
 
-        tets.extend_from_slice(&vec![a-1, b-1, c-1, d-1]);
+        for _ in 0..n {
+            // Element type 4 is a 4-node tetrahedron; every other type is skipped
+            if element_type == 4 {
+                let element_line = split_string(&lines[line_number]);
+                let a: i32 = element_line[1].parse()?;
+                let b: i32 = element_line[2].parse()?;
+                let c: i32 = element_line[3].parse()?;
+                let d: i32 = element_line[4].parse()?;
+
+                tets.extend_from_slice(&vec![a-1, b-1, c-1, d-1]);
+                n_tets += 1;
+            }
 
-        line_number += 1;
+            line_number += 1;
+        }
     }
 
     // Create 2D arrays of points and tets
     let points = Array2::from_shape_vec((n_points, 3), points)?;
     let tets = Array2::from_shape_vec((n_tets, 4), tets)?;
 
-    // Remove temporary file
-    Command::new("rm").arg("output.geo").spawn()?;
-    Command::new("rm").arg("output.msh").spawn()?;
-
     return Ok(Mesh {points, tets});
 }
 
-/// Create voxel model from a mesh
-pub fn voxelize(mesh: &Mesh) -> Result<VoxelModel, Box<dyn Error>> {
-    let mesh = mesh.clone();
-
-    // Get min and max values in each axis
-    let mut x_min = mesh.points[[0, 0]];
-    let mut x_max = mesh.points[[0, 0]];
-    let mut y_min = mesh.points[[0, 1]];
-    let mut y_max = mesh.points[[0, 1]];
-    let mut z_min = mesh.points[[0, 2]];
-    let mut z_max = mesh.points[[0, 2]];
-
-    for point in mesh.points.genrows() {
+/// Get the component-wise min and max of every point in a mesh, as
+/// `(x_min, x_max, y_min, y_max, z_min, z_max)`
+fn mesh_bounds(points: &Array2<f32>) -> (f32, f32, f32, f32, f32, f32) {
+    let mut x_min = points[[0, 0]];
+    let mut x_max = points[[0, 0]];
+    let mut y_min = points[[0, 1]];
+    let mut y_max = points[[0, 1]];
+    let mut z_min = points[[0, 2]];
+    let mut z_max = points[[0, 2]];
+
+    for point in points.genrows() {
         if point[0] < x_min { x_min = point[0]; }
         if point[0] > x_max { x_max = point[0]; }
 
@@ -195,129 +295,318 @@ pub fn voxelize(mesh: &Mesh) -> Result<VoxelModel, Box<dyn Error>> {
         if point[2] > z_max { z_max = point[2]; }
     }
 
-    // Round min and max values to integers
-    let x_min = x_min.round() as i32;
-    let x_max = x_max.round() as i32;
-    let y_min = y_min.round() as i32;
-    let y_max = y_max.round() as i32;
-    let z_min = z_min.round() as i32;
-    let z_max = z_max.round() as i32;
-
-    // Calculate x, y, and z lengths
-    let x_len = (x_max - x_min) as usize;
-    let y_len = (y_max - y_min) as usize;
-    let z_len = (z_max - z_min) as usize;
-    // Calculate total number of voxels
-    let n_voxels = x_len * y_len * z_len;
-
-    // Create array to store coordinates of each voxel
-    let mut grid_xyz: Array2<f32> = Array2::zeros((n_voxels, 4));
-    // Create array to store position of each voxel in grid
-    let mut grid_ijk: Array2<usize> = Array2::zeros((n_voxels, 3));
-
-    // Find coordinates of center of first voxel
-    let x_start: f32 = x_min as f32 + 0.5;
-    let y_start: f32 = y_min as f32 + 0.5;
-    let z_start: f32 = z_min as f32 + 0.5;
-
-    // Generate list of voxels
-    let mut row = 0;
-    for i in 0..x_len {
-        for j in 0..y_len {
-            for k in 0..z_len {
-                let mut row_xyz = grid_xyz.row_mut(row);
-                row_xyz[0] = x_start + i as f32;
-                row_xyz[1] = y_start + j as f32;
-                row_xyz[2] = z_start + k as f32;
-                row_xyz[3] = 1.0;
-
-                let mut row_ijk = grid_ijk.row_mut(row);
-                row_ijk[0] = i;
-                row_ijk[1] = j;
-                row_ijk[2] = k;
-
-                row += 1;
-            }
-        }
+    return (x_min, x_max, y_min, y_max, z_min, z_max);
+}
+
+/// A voxel grid's dimensions and placement in model space, computed once from
+/// a mesh's bounding box and shared by every rasterization path so they all
+/// agree on voxel centers
+#[derive(Clone, Copy)]
+struct GridSpec {
+    x_len: usize,
+    y_len: usize,
+    z_len: usize,
+    /// Model-space coordinates of the center of voxel `[0, 0, 0]`
+    x_start: f32,
+    y_start: f32,
+    z_start: f32,
+    resolution: f32
+}
+
+impl GridSpec {
+    /// Build the grid spanning `bounds` at the given `resolution`
+    fn new(bounds: (f32, f32, f32, f32, f32, f32), resolution: f32) -> GridSpec {
+        let (x_min, x_max, y_min, y_max, z_min, z_max) = bounds;
+
+        // Calculate x, y, and z lengths, in voxels of `resolution` model units
+        let x_len = ((x_max - x_min) / resolution).ceil() as usize;
+        let y_len = ((y_max - y_min) / resolution).ceil() as usize;
+        let z_len = ((z_max - z_min) / resolution).ceil() as usize;
+
+        // Find coordinates of center of first voxel
+        let x_start = x_min + 0.5 * resolution;
+        let y_start = y_min + 0.5 * resolution;
+        let z_start = z_min + 0.5 * resolution;
+
+        return GridSpec { x_len, y_len, z_len, x_start, y_start, z_start, resolution };
     }
+}
+
+/// Create voxel model from a mesh
+pub fn voxelize(mesh: &Mesh, resolution: f32) -> Result<VoxelModel, Box<dyn Error>> {
+    let mesh = mesh.clone();
+
+    // Get min and max values in each axis
+    let (x_min, x_max, y_min, y_max, z_min, z_max) = mesh_bounds(&mesh.points);
+    let grid = GridSpec::new((x_min, x_max, y_min, y_max, z_min, z_max), resolution);
 
-    // Create complete voxel grid
-    let model: Arc<Mutex<Array3<u8>>> = Arc::new(Mutex::new(Array3::zeros((x_len, y_len, z_len))));
-    // Get Arc pointers to the grid arrays and the point array
-    let grid_ijk: Arc<Array2<usize>> = Arc::new(grid_ijk);
-    let grid_xyz: Arc<Array2<f32>> = Arc::new(grid_xyz);
-    let points: Arc<Array2<f32>> = Arc::new(mesh.points);
+    // Fill in every voxel that falls inside one of the mesh's tets
+    let voxels = rasterize_tets(Arc::new(mesh.tets), Arc::new(mesh.points), grid);
 
-    // Create a vector to hold the handle of all the threads
+    // Initialize and return a new VoxelModel
+    return Ok(VoxelModel {
+        voxels,
+        x_len: grid.x_len,
+        y_len: grid.y_len,
+        z_len: grid.z_len,
+        materials: vec![default_material_row(0), default_material_row(1)],
+        origin: [x_min, y_min, z_min],
+        resolution
+    });
+}
+
+/// Rasterize a mesh's tets into a `0`/`1` grid of the given dimensions,
+/// using a bounded pool of worker threads (one per tet chunk, each with its
+/// own scratch grid) so there is no lock contention in the hot loop
+fn rasterize_tets(tets: Arc<Array2<i32>>, points: Arc<Array2<f32>>, grid: GridSpec) -> Array3<u8> {
+    let GridSpec { x_len, y_len, z_len, x_start, y_start, z_start, resolution } = grid;
+    // Split the tets into a contiguous chunk per worker, so a mesh with many
+    // tets spawns a handful of threads instead of one per tet
+    let n_workers = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let n_tets = tets.nrows();
+    let chunk_size = (n_tets + n_workers - 1) / n_workers.max(1);
+
+    // Create a vector to hold the handle of all the worker threads
     let mut thread_handles = vec![];
 
-    // Process every tet
-    for tet in mesh.tets.genrows() {
-        // Create copy of tet to ensure that it lives long enough
-        let tet = tet.to_owned();
+    // Process every tet, with each worker filling its own scratch grid
+    for chunk_start in (0..n_tets).step_by(chunk_size.max(1)) {
+        let chunk_end = (chunk_start + chunk_size).min(n_tets);
 
-        // Get copies of the pointers to the model, the grid arrays, and the point array
-        let model = Arc::clone(&model);
-        let grid_ijk = Arc::clone(&grid_ijk);
-        let grid_xyz = Arc::clone(&grid_xyz);
+        // Get copies of the pointers to the tet and point arrays
+        let tets = Arc::clone(&tets);
         let points = Arc::clone(&points);
 
-        // Create new thread
+        // Create new worker thread
         let handle = thread::spawn(move || {
-            // Construct a complete representation of the tet
-            let mut tet_full = Array2::zeros((4, 4));
-            for i in 0..4 {
-                for j in 0..3 {
-                    tet_full[[i, j]] = points[[tet[i] as usize, j]];
+            // Each worker owns its scratch grid, so there is no lock contention
+            let mut scratch: Array3<u8> = Array3::zeros((x_len, y_len, z_len));
+
+            for t in chunk_start..chunk_end {
+                let tet = tets.row(t);
+
+                // Construct a complete representation of the tet
+                let mut tet_full = Array2::zeros((4, 4));
+                for i in 0..4 {
+                    for j in 0..3 {
+                        tet_full[[i, j]] = points[[tet[i] as usize, j]];
+                    }
+                    tet_full[[i, 3]] = 1.0;
                 }
-                tet_full[[i, 3]] = 1.0;
-            }
 
-            // Get the inverse of the tet
-            let mut inverse = tet_full.inv().unwrap();
-            inverse = inverse.t().to_owned();
-
-            // Initialize an array to hold the voxel within this tet
-            let mut filled_voxels = Vec::with_capacity(n_voxels);
-            for i in 0..n_voxels {
-                let x = grid_ijk[[i, 0]];
-                let y = grid_ijk[[i, 1]];
-                let z = grid_ijk[[i, 2]];
-                let point = vec![x, y, z];
-
-                let mut dot_products: Array1<f32> = Array1::zeros(4);
-                for j in 0..4 {
-                    dot_products[j] = inverse.row(j).dot(&grid_xyz.row(i));
+                // Get the inverse of the tet
+                let mut inverse = tet_full.inv().unwrap();
+                inverse = inverse.t().to_owned();
+
+                // Find the tet's axis-aligned bounding box from its four vertices
+                let mut tet_x_min = tet_full[[0, 0]];
+                let mut tet_x_max = tet_full[[0, 0]];
+                let mut tet_y_min = tet_full[[0, 1]];
+                let mut tet_y_max = tet_full[[0, 1]];
+                let mut tet_z_min = tet_full[[0, 2]];
+                let mut tet_z_max = tet_full[[0, 2]];
+                for i in 1..4 {
+                    if tet_full[[i, 0]] < tet_x_min { tet_x_min = tet_full[[i, 0]]; }
+                    if tet_full[[i, 0]] > tet_x_max { tet_x_max = tet_full[[i, 0]]; }
+
+                    if tet_full[[i, 1]] < tet_y_min { tet_y_min = tet_full[[i, 1]]; }
+                    if tet_full[[i, 1]] > tet_y_max { tet_y_max = tet_full[[i, 1]]; }
+
+                    if tet_full[[i, 2]] < tet_z_min { tet_z_min = tet_full[[i, 2]]; }
+                    if tet_full[[i, 2]] > tet_z_max { tet_z_max = tet_full[[i, 2]]; }
                 }
 
-                // Check if point is inside tet
-                if all_in_range(&dot_products, 0.0, 1.0) {
-                    filled_voxels.push(point);
+                // Convert the tet's bounding box into an inclusive, clamped range of voxel indices
+                let i_start = clamp_floor((tet_x_min - x_start) / resolution, x_len);
+                let i_end = clamp_ceil((tet_x_max - x_start) / resolution, x_len);
+                let j_start = clamp_floor((tet_y_min - y_start) / resolution, y_len);
+                let j_end = clamp_ceil((tet_y_max - y_start) / resolution, y_len);
+                let k_start = clamp_floor((tet_z_min - z_start) / resolution, z_len);
+                let k_end = clamp_ceil((tet_z_max - z_start) / resolution, z_len);
+
+                // Only test the voxels inside the tet's bounding box, not the whole grid
+                for i in i_start..i_end {
+                    for j in j_start..j_end {
+                        for k in k_start..k_end {
+                            let point_xyz = Array1::from(vec![
+                                x_start + i as f32 * resolution,
+                                y_start + j as f32 * resolution,
+                                z_start + k as f32 * resolution,
+                                1.0
+                            ]);
+
+                            let mut dot_products: Array1<f32> = Array1::zeros(4);
+                            for d in 0..4 {
+                                dot_products[d] = inverse.row(d).dot(&point_xyz);
+                            }
+
+                            // Check if point is inside tet
+                            if all_in_range(&dot_products, 0.0, 1.0) {
+                                scratch[[i, j, k]] = 1;
+                            }
+                        }
+                    }
                 }
             }
 
-            // Lock the mutex to the model
-            let mut model = model.lock().unwrap();
-            // Fill in the voxels within the tet
-            for point in filled_voxels {
-                model[[point[0], point[1], point[2]]] = 1;
-            }
+            return scratch;
         });
-        // Store the handle to the thread
+        // Store the handle to the worker thread
         thread_handles.push(handle);
     }
 
-    // Wait for all the thread to finish
+    // OR every worker's scratch grid together into the final result
+    let mut voxels: Array3<u8> = Array3::zeros((x_len, y_len, z_len));
     for handle in thread_handles {
-        handle.join().unwrap();
+        let scratch = handle.join().unwrap();
+        Zip::from(&mut voxels).and(&scratch).for_each(|voxel, &filled| *voxel |= filled);
+    }
+
+    return voxels;
+}
+
+/// Create voxel model from a mesh by building a BVH over the mesh's tets and,
+/// for each voxel center, querying it for the handful of tets whose box could
+/// contain that point instead of testing every tet against every voxel
+pub fn voxelize_bvh(mesh: &Mesh, resolution: f32) -> Result<VoxelModel, Box<dyn Error>> {
+    let mesh = mesh.clone();
+
+    // Get min and max values in each axis
+    let (x_min, x_max, y_min, y_max, z_min, z_max) = mesh_bounds(&mesh.points);
+    let grid = GridSpec::new((x_min, x_max, y_min, y_max, z_min, z_max), resolution);
+    let GridSpec { x_len, y_len, z_len, x_start, y_start, z_start, .. } = grid;
+
+    // Build the inverse matrix of every tet, alongside the tet's AABB for the BVH
+    let mut inverses = Vec::with_capacity(mesh.tets.nrows());
+    let mut boxes = Vec::with_capacity(mesh.tets.nrows());
+    for (index, tet) in mesh.tets.genrows().into_iter().enumerate() {
+        let mut tet_full = Array2::zeros((4, 4));
+        let mut vertices = Vec::with_capacity(4);
+        for i in 0..4 {
+            for j in 0..3 {
+                tet_full[[i, j]] = mesh.points[[tet[i] as usize, j]];
+            }
+            tet_full[[i, 3]] = 1.0;
+
+            vertices.push([tet_full[[i, 0]], tet_full[[i, 1]], tet_full[[i, 2]]]);
+        }
+
+        let mut inverse = tet_full.inv()?;
+        inverse = inverse.t().to_owned();
+
+        inverses.push(inverse);
+        boxes.push((AABB::from_points(&vertices), index));
+    }
+
+    let tree = BVH::build(boxes);
+
+    // Fill in the grid by querying the BVH once per voxel center
+    let mut model: Array3<u8> = Array3::zeros((x_len, y_len, z_len));
+    for i in 0..x_len {
+        for j in 0..y_len {
+            for k in 0..z_len {
+                let point = [
+                    x_start + i as f32 * resolution,
+                    y_start + j as f32 * resolution,
+                    z_start + k as f32 * resolution
+                ];
+
+                let mut candidates = Vec::new();
+                tree.query(point, &mut candidates);
+
+                if candidates.is_empty() {
+                    continue;
+                }
+
+                let point_xyz = Array1::from(vec![point[0], point[1], point[2], 1.0]);
+                for tet_index in candidates {
+                    let inverse = &inverses[tet_index];
+
+                    let mut dot_products: Array1<f32> = Array1::zeros(4);
+                    for d in 0..4 {
+                        dot_products[d] = inverse.row(d).dot(&point_xyz);
+                    }
+
+                    if all_in_range(&dot_products, 0.0, 1.0) {
+                        model[[i, j, k]] = 1;
+                        break;
+                    }
+                }
+            }
+        }
     }
 
     // Initialize and return a new VoxelModel
     return Ok(VoxelModel {
-        voxels: model.lock().unwrap().to_owned(),
+        voxels: model,
         x_len,
         y_len,
-        z_len
+        z_len,
+        materials: vec![default_material_row(0), default_material_row(1)],
+        origin: [x_min, y_min, z_min],
+        resolution
+    });
+}
+
+/// Create a voxel model from several meshes, each tagged with a material id,
+/// rasterized into one shared grid. Meshes are processed in order, and where
+/// two meshes both claim a voxel the later mesh in `meshes` wins.
+pub fn voxelize_multi(meshes: &[(Mesh, MaterialId)], resolution: f32) -> Result<VoxelModel, Box<dyn Error>> {
+    // Material 0 is reserved for background; stamping it into `voxels` would be
+    // indistinguishable from never having rasterized the mesh at all
+    if meshes.iter().any(|(_, material)| *material == 0) {
+        return Err("material id 0 is reserved for background and can't be assigned to a mesh".into());
+    }
+
+    // Get the combined bounding box across every mesh, so they all rasterize into one grid
+    let mut x_min = f32::INFINITY;
+    let mut x_max = f32::NEG_INFINITY;
+    let mut y_min = f32::INFINITY;
+    let mut y_max = f32::NEG_INFINITY;
+    let mut z_min = f32::INFINITY;
+    let mut z_max = f32::NEG_INFINITY;
+
+    for (mesh, _) in meshes {
+        let (mesh_x_min, mesh_x_max, mesh_y_min, mesh_y_max, mesh_z_min, mesh_z_max) = mesh_bounds(&mesh.points);
+
+        if mesh_x_min < x_min { x_min = mesh_x_min; }
+        if mesh_x_max > x_max { x_max = mesh_x_max; }
+        if mesh_y_min < y_min { y_min = mesh_y_min; }
+        if mesh_y_max > y_max { y_max = mesh_y_max; }
+        if mesh_z_min < z_min { z_min = mesh_z_min; }
+        if mesh_z_max > z_max { z_max = mesh_z_max; }
+    }
+
+    let grid = GridSpec::new((x_min, x_max, y_min, y_max, z_min, z_max), resolution);
+
+    let mut voxels: Array3<u8> = Array3::zeros((grid.x_len, grid.y_len, grid.z_len));
+    let mut max_material = 0;
+
+    // Rasterize each mesh in turn and stamp its material id into the shared grid
+    for (mesh, material) in meshes {
+        let mesh = mesh.clone();
+        let mask = rasterize_tets(Arc::new(mesh.tets), Arc::new(mesh.points), grid);
+
+        Zip::from(&mut voxels).and(&mask).for_each(|voxel, &filled| {
+            if filled != 0 { *voxel = *material; }
+        });
+
+        if *material > max_material { max_material = *material; }
+    }
+
+    // Build a materials table with one row for every id from 0 up to the highest
+    // id used, so each voxel value indexes its row (ids in between get a row too)
+    let materials: Vec<[f32; 10]> = (0..=max_material).map(default_material_row).collect();
+
+    // Initialize and return a new VoxelModel
+    return Ok(VoxelModel {
+        voxels,
+        x_len: grid.x_len,
+        y_len: grid.y_len,
+        z_len: grid.z_len,
+        materials,
+        origin: [x_min, y_min, z_min],
+        resolution
     });
 }
 
@@ -331,6 +620,26 @@ fn split_string(s: &str) -> Vec<&str> {
     return parts;
 }
 
+/// Round an offset down to a voxel index, clamped to the `0..=len` range
+fn clamp_floor(offset: f32, len: usize) -> usize {
+    if offset <= 0.0 {
+        return 0;
+    }
+
+    let i = offset.floor() as usize;
+    return i.min(len);
+}
+
+/// Round an offset up to an exclusive voxel index bound, clamped to the valid `0..=len` range
+fn clamp_ceil(offset: f32, len: usize) -> usize {
+    if offset < 0.0 {
+        return 0;
+    }
+
+    let i = offset.ceil() as usize + 1;
+    return i.min(len);
+}
+
 /// Check if all the values within an array are inside of a given range, with a tolerance
 fn all_in_range(array: &Array1<f32>, low: f32, high: f32) -> bool {
     for i in array.iter() {
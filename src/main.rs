@@ -1,28 +1,54 @@
 extern crate stl2vf;
 
 use std::env;
-use stl2vf::{voxelize, from_stl, write_to_vf};
+use stl2vf::{voxelize_multi, from_stl, write_to_vf, MaterialId};
 use std::time::Instant;
 
 fn main() {
-    // Get command line arguments
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 3 {
-        println!("Please specify input and output files");
+    // Get command line arguments, pulling out an optional -r/--resolution flag
+    let mut resolution: f32 = 1.0;
+    let mut positional: Vec<String> = Vec::new();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "-r" || arg == "--resolution" {
+            let value = args.next().expect("-r/--resolution requires a value");
+            resolution = value.parse().expect("Invalid resolution");
+        } else {
+            positional.push(arg);
+        }
+    }
+
+    if positional.len() < 2 {
+        println!("Please specify at least one input.stl[:materialIndex] and an output file, optionally with -r <resolution>");
         return;
     }
-    let input_file_name = &args[1];
-    let output_file_name = &args[2];
+    let output_file_name = positional.last().unwrap().clone();
+    let input_args = &positional[..positional.len() - 1];
 
-    // Generate mesh from STL
-    let mesh = from_stl(input_file_name).expect("Error converting STL");
-    println!("Mesh loaded");
+    // Generate a mesh, tagged with its material id, from every input argument
+    let mut meshes = Vec::with_capacity(input_args.len());
+    for input_arg in input_args {
+        let mut parts = input_arg.splitn(2, ':');
+        let input_file_name = parts.next().unwrap();
+        let material: MaterialId = match parts.next() {
+            Some(material) => material.parse().expect("Invalid material index"),
+            None => 1
+        };
+        if material == 0 {
+            panic!("Material index 0 is reserved for background and can't be assigned to an input mesh");
+        }
+
+        let mesh = from_stl(input_file_name).expect("Error converting STL");
+        meshes.push((mesh, material));
+    }
+    println!("Meshes loaded");
 
-    // Voxelize mesh
-    let model = voxelize(&mesh).expect("Error voxelizing model");
+    // Voxelize meshes
+    let model = voxelize_multi(&meshes, resolution).expect("Error voxelizing model");
     println!("Model voxelized");
 
     // Write voxel model to file
-    write_to_vf(&model, output_file_name).expect("Error writing VF file");
+    write_to_vf(&model, &output_file_name).expect("Error writing VF file");
     println!("VF file written");
 }